@@ -5,24 +5,40 @@
 //!
 //! Graceful shutdown is handled via `Drop`: when the pool is dropped,
 //! the sender is closed, causing workers to exit after finishing their current job.
+//!
+//! Jobs that panic no longer take their worker down with them: each job runs
+//! behind `catch_unwind`, so the worker logs the panic and keeps pulling jobs.
+//! A pool created with [`ThreadPool::new_supervised`] goes further and retires
+//! a worker that panics repeatedly, replacing it with a fresh one on the next
+//! [`ThreadPool::reap_and_respawn`] call so the pool stays at full strength.
 
 use std::{
+    panic::{self, AssertUnwindSafe},
     sync::{Arc, Mutex, mpsc},
     thread,
 };
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+/// How many consecutive panics a worker tolerates, in supervised pools,
+/// before retiring itself so it can be respawned.
+const PANIC_THRESHOLD: u32 = 3;
+
 /// A thread pool that executes jobs concurrently across a fixed number of worker threads.
 pub struct ThreadPool {
     workers: Vec<Worker>,
     sender: Option<mpsc::Sender<Job>>,
+    receiver: Arc<Mutex<mpsc::Receiver<Job>>>,
+    supervised: bool,
 }
 
 impl ThreadPool {
     /// Create a new ThreadPool.
     ///
-    /// `size` is the number of worker threads.
+    /// `size` is the number of worker threads. A worker whose job panics
+    /// logs the panic and keeps running, but a worker that panics
+    /// repeatedly is not replaced — use [`ThreadPool::new_supervised`] if
+    /// you want that.
     ///
     /// # Panics
     ///
@@ -34,6 +50,31 @@ impl ThreadPool {
     /// let pool = riotpool::ThreadPool::new(8);
     /// ```
     pub fn new(size: usize) -> Self {
+        Self::build(size, false)
+    }
+
+    /// Create a new ThreadPool that replaces workers retired after
+    /// repeated job panics.
+    ///
+    /// Retired workers aren't respawned automatically — call
+    /// [`ThreadPool::reap_and_respawn`] periodically (e.g. once per
+    /// accept-loop iteration) to bring the pool back up to `size` live
+    /// threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// let pool = riotpool::ThreadPool::new_supervised(8);
+    /// ```
+    pub fn new_supervised(size: usize) -> Self {
+        Self::build(size, true)
+    }
+
+    fn build(size: usize, supervised: bool) -> Self {
         assert!(size > 0);
 
         let mut workers = Vec::with_capacity(size);
@@ -41,18 +82,22 @@ impl ThreadPool {
         let receiver = Arc::new(Mutex::new(receiver));
 
         for id in 1..=size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+            workers.push(Worker::new(id, Arc::clone(&receiver), supervised));
         }
 
         Self {
             workers,
             sender: Some(sender),
+            receiver,
+            supervised,
         }
     }
 
     /// Execute a closure on one of the worker threads.
     ///
     /// The closure must be `Send + 'static` and will be executed exactly once.
+    /// If it panics, the panic is caught and logged; it will not bring down
+    /// the worker that ran it.
     ///
     /// # Panics
     ///
@@ -61,6 +106,7 @@ impl ThreadPool {
     /// # Examples
     ///
     /// ```no_run
+    /// let pool = riotpool::ThreadPool::new(8);
     /// pool.execute(|| {
     ///     println!("Hello from a worker thread!");
     /// });
@@ -76,6 +122,41 @@ impl ThreadPool {
             .send(job)
             .expect("sending job to worker failed");
     }
+
+    /// Replaces any worker that has retired itself (a supervised worker
+    /// that hit [`PANIC_THRESHOLD`] consecutive panics) with a fresh one
+    /// carrying the same id, restoring `size` live threads.
+    ///
+    /// A no-op on pools created with [`ThreadPool::new`], since their
+    /// workers never retire on their own.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// let mut pool = riotpool::ThreadPool::new_supervised(8);
+    /// pool.reap_and_respawn();
+    /// ```
+    pub fn reap_and_respawn(&mut self) {
+        if !self.supervised {
+            return;
+        }
+
+        for worker in &mut self.workers {
+            let retired = worker
+                .thread
+                .as_ref()
+                .is_some_and(thread::JoinHandle::is_finished);
+
+            if retired {
+                if let Some(thread) = worker.thread.take() {
+                    let _ = thread.join();
+                }
+
+                println!("respawning worker {}", worker.id);
+                *worker = Worker::new(worker.id, Arc::clone(&self.receiver), self.supervised);
+            }
+        }
+    }
 }
 
 /// Gracefully shuts down all workers when the pool is dropped.
@@ -101,21 +182,52 @@ struct Worker {
 
 impl Worker {
     /// Creates a new worker thread that continuously receives jobs from the shared receiver.
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Self {
-        let thread = thread::spawn(move || loop {
-            let message = receiver
-                .lock()
-                .expect("failed to acquire the lock")
-                .recv();
-
-            match message {
-                Ok(job) => {
-                    println!("Worker {id} got a job; executing.");
-                    job();
-                }
-                Err(_) => {
-                    println!("Worker {id} disconnected; shutting down.");
-                    break;
+    ///
+    /// Each job runs behind `catch_unwind`, so a panicking job is logged and
+    /// the loop keeps going. When `supervised` is true, a worker that panics
+    /// [`PANIC_THRESHOLD`] times in a row retires itself instead of
+    /// tolerating the job forever, leaving respawning to
+    /// [`ThreadPool::reap_and_respawn`].
+    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>, supervised: bool) -> Self {
+        let thread = thread::spawn(move || {
+            let mut consecutive_panics = 0;
+
+            loop {
+                let message = receiver
+                    .lock()
+                    .expect("failed to acquire the lock")
+                    .recv();
+
+                match message {
+                    Ok(job) => {
+                        println!("Worker {id} got a job; executing.");
+
+                        match panic::catch_unwind(AssertUnwindSafe(job)) {
+                            Ok(()) => consecutive_panics = 0,
+                            Err(payload) => {
+                                consecutive_panics += 1;
+                                let message = payload
+                                    .downcast_ref::<&str>()
+                                    .copied()
+                                    .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                                    .unwrap_or("<non-string panic payload>");
+                                eprintln!(
+                                    "Worker {id} job panicked ({consecutive_panics}/{PANIC_THRESHOLD}): {message}"
+                                );
+
+                                if supervised && consecutive_panics >= PANIC_THRESHOLD {
+                                    eprintln!(
+                                        "Worker {id} hit {PANIC_THRESHOLD} consecutive panics; retiring for respawn."
+                                    );
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Err(_) => {
+                        println!("Worker {id} disconnected; shutting down.");
+                        break;
+                    }
                 }
             }
         });
@@ -125,4 +237,41 @@ impl Worker {
             thread: Some(thread),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    #[test]
+    fn a_panicking_job_does_not_take_down_the_pool() {
+        let pool = ThreadPool::new(1);
+        let (tx, rx) = channel();
+
+        pool.execute(|| panic!("boom"));
+        pool.execute(move || tx.send(()).unwrap());
+
+        rx.recv_timeout(Duration::from_secs(5))
+            .expect("pool should keep running jobs after a panic");
+    }
+
+    #[test]
+    fn supervised_pool_respawns_a_worker_after_repeated_panics() {
+        let mut pool = ThreadPool::new_supervised(1);
+        let (tx, rx) = channel();
+
+        for _ in 0..PANIC_THRESHOLD {
+            pool.execute(|| panic!("boom"));
+        }
+        // Give the worker a moment to observe its own panics and retire.
+        thread::sleep(Duration::from_millis(200));
+
+        pool.reap_and_respawn();
+        pool.execute(move || tx.send(()).unwrap());
+
+        rx.recv_timeout(Duration::from_secs(5))
+            .expect("respawned worker should still run jobs");
+    }
+}
@@ -12,79 +12,464 @@ use std::{
     fs,
     io::{self, BufRead, BufReader, Write},
     net::{Ipv4Addr, SocketAddrV4, TcpListener, TcpStream},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc,
+    },
     thread,
     time::{Duration, Instant},
 };
 
-/// Handles a single TCP connection by reading the request line,
-/// determining the appropriate response, and writing it back to the client.
+use riotpool::ThreadPool;
+
+/// Guesses a `Content-Type` value from a served file's extension.
+///
+/// Falls back to `application/octet-stream` for anything unrecognized.
+fn content_type_for(filename: &str) -> &'static str {
+    match Path::new(filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+    {
+        Some("html") => "text/html",
+        Some("css") => "text/css",
+        Some("js") => "text/javascript",
+        Some("png") => "image/png",
+        Some("svg") => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
+
+/// A parsed HTTP request: the request line split into its three parts,
+/// plus whatever headers preceded the blank line that ends the head.
+///
+/// The request body, if any, is not read — none of the routes handled by
+/// this crate currently need one.
+pub struct Request {
+    pub method: String,
+    pub target: String,
+    pub version: String,
+    pub headers: Vec<(String, String)>,
+}
+
+/// Reads a full HTTP request head (request line + headers) from `stream`.
+///
+/// Stops at the first blank line, per HTTP/1.1 framing.
+///
+/// # Errors
+///
+/// Returns an error if the stream closes before a request line is sent, if
+/// the request line lacks a method, target or version, if reading a line fails (e.g. the client resets the connection mid-headers),
+/// or if a header line has no `:` separator. `handle_connection` maps any of
+/// these to a `400` rather than letting them panic.
+pub fn parse_request(stream: &TcpStream) -> io::Result<Request> {
+    let mut lines = BufReader::new(stream).lines();
+
+    let request_line = match lines.next() {
+        Some(line) => line?,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "stream closed before a request line was sent",
+            ));
+        }
+    };
+
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("malformed request line: {request_line:?}"),
+        ));
+    };
+    let (method, target, version) = (method.to_string(), target.to_string(), version.to_string());
+
+    let mut headers = Vec::new();
+    for line in lines {
+        let line = line?;
+        if line.is_empty() {
+            break;
+        }
+
+        let (name, value) = line.split_once(':').ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("malformed header line: {line:?}"))
+        })?;
+        headers.push((name.to_string(), value.trim().to_string()));
+    }
+
+    Ok(Request {
+        method,
+        target,
+        version,
+        headers,
+    })
+}
+
+/// Resolves a request target to a status line and the file that should be
+/// served for it.
 ///
 /// Currently, supports:
-/// - `GET / HTTP/1.1` → serves `public/index.html`
-/// - `GET /sleep HTTP/1.1` → sleeps for 5 seconds then serves `public/index.html`
-/// - All other requests → serves files that match the files that exist in `public/` if not it returns a 404 error
+/// - `/` → serves `public/index.html`
+/// - `/sleep` → sleeps for 5 seconds then serves `public/index.html`
+/// - Anything else → serves the matching file under `public/` if it exists,
+///   otherwise `public/404.html`
+fn resolve_target(target: &str) -> (&'static str, String) {
+    if target == "/" || target.is_empty() {
+        ("HTTP/1.1 200 OK", "public/index.html".to_string())
+    } else if target == "/sleep" {
+        thread::sleep(Duration::from_secs(5));
+        ("HTTP/1.1 200 OK", "public/index.html".to_string())
+    } else {
+        // Serve any other file from the "public" directory
+        let sanitized_path = target.strip_prefix('/').unwrap_or(target);
+
+        // Basic security: prevent directory traversal
+        if sanitized_path.contains("..") || sanitized_path.contains('\\') {
+            ("HTTP/1.1 400 BAD REQUEST", "public/400.html".to_string())
+        } else {
+            let full_path = format!("public/{}", sanitized_path);
+
+            // If file exists → serve it, else 404
+            if Path::new(&full_path).exists() {
+                ("HTTP/1.1 200 OK", full_path)
+            } else {
+                ("HTTP/1.1 404 NOT FOUND", "public/404.html".to_string())
+            }
+        }
+    }
+}
+
+/// Tells the accept loop run by [`serve`] whether to keep serving
+/// connections or shut the server down.
+///
+/// Returned by [`handle_connection`] so that a `GET /stop` handled on a
+/// worker thread can reach the accept loop, which owns the `ThreadPool` and
+/// the `TcpListener`.
+pub enum ServerControl {
+    Continue,
+    Stop,
+}
+
+/// Shared, lock-free request counters exposed over `GET /metrics`.
+///
+/// Every counter is a relaxed atomic. They're bumped from every worker
+/// thread on the hot path, where a `Mutex` would serialize otherwise
+/// independent requests just to keep a tally nothing needs strict ordering
+/// on.
+#[derive(Default)]
+pub struct Metrics {
+    total_requests: AtomicUsize,
+    responses_2xx: AtomicUsize,
+    responses_4xx: AtomicUsize,
+    responses_5xx: AtomicUsize,
+    bytes_served: AtomicUsize,
+}
+
+impl Metrics {
+    /// Creates a fresh, zeroed set of counters behind an `Arc`, ready to be
+    /// cloned into each job and passed to [`handle_connection`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// let metrics = tidepool::Metrics::new();
+    /// ```
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    fn record_response(&self, status_line: &str, body_len: usize) {
+        self.total_requests.fetch_add(1, Ordering::Relaxed);
+        self.bytes_served.fetch_add(body_len, Ordering::Relaxed);
+
+        let bucket = match status_line.split_whitespace().nth(1).and_then(|code| code.bytes().next()) {
+            Some(b'2') => &self.responses_2xx,
+            Some(b'4') => &self.responses_4xx,
+            Some(b'5') => &self.responses_5xx,
+            _ => return,
+        };
+        bucket.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Renders the current counters as a small `text/plain` body.
+    fn render(&self) -> String {
+        format!(
+            "total_requests {}\n2xx {}\n4xx {}\n5xx {}\nbytes_served {}\n",
+            self.total_requests.load(Ordering::Relaxed),
+            self.responses_2xx.load(Ordering::Relaxed),
+            self.responses_4xx.load(Ordering::Relaxed),
+            self.responses_5xx.load(Ordering::Relaxed),
+            self.bytes_served.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// A hardcoded fallback body, used when even the on-disk error page for a
+/// failed request can't be read, so a broken or missing `public/` directory
+/// never turns into a panic.
+const FALLBACK_ERROR_BODY: &str = "<html><body><h1>Something went wrong</h1></body></html>";
+
+/// Writes `status_line` with the contents of `filename` as the body, or
+/// [`FALLBACK_ERROR_BODY`] if `filename` itself can't be read. With
+/// `send_body` false (a `HEAD` request) only the headers are written.
+///
+/// Returns the number of body bytes written, so callers can pass an
+/// accurate count to [`Metrics::record_response`].
+fn write_error_response(
+    stream: &mut TcpStream,
+    status_line: &str,
+    filename: &str,
+    send_body: bool,
+) -> io::Result<usize> {
+    let on_disk = fs::read(filename).ok();
+    let (body, content_type): (&[u8], _) = match &on_disk {
+        Some(bytes) => (bytes.as_slice(), content_type_for(filename)),
+        None => (FALLBACK_ERROR_BODY.as_bytes(), "text/html"),
+    };
+
+    let mut response = format!(
+        "{status_line}\r\nContent-Length: {}\r\nContent-Type: {content_type}\r\n\r\n",
+        body.len()
+    )
+    .into_bytes();
+    if send_body {
+        response.extend_from_slice(body);
+    }
+
+    stream.write_all(&response)?;
+    Ok(if send_body { body.len() } else { 0 })
+}
+
+/// Handles a single TCP connection by reading the full request, routing on
+/// its method and target, and writing the response back to the client.
+///
+/// `GET` and `HEAD` are routed through [`resolve_target`]; `HEAD` sends the
+/// same headers as `GET` but an empty body. Any other method gets a `405`.
+/// `GET /stop` is handled before routing: it replies `200 OK` and returns
+/// [`ServerControl::Stop`] to ask the caller to shut down. `GET /metrics`
+/// renders `metrics` as `text/plain` instead of touching the filesystem.
+///
+/// A garbled or unreadable request is answered with a `400`; a served file
+/// that's missing (including a missing `public/404.html` or `public/400.html`
+/// error page itself) falls back through [`write_error_response`] instead of
+/// panicking. The only way this returns `Err` is if writing to `stream`
+/// itself fails, e.g. because the client is already gone — callers should
+/// log that and move on rather than treat it as fatal.
+///
+/// # Errors
+///
+/// Returns an error if writing the response to `stream` fails.
+///
+/// # Examples
+///
+/// ```ignore
+/// // `stream` here stands in for a real, already-accepted `TcpStream`;
+/// // doctests don't have a live connection to construct one from.
+/// use std::net::TcpStream;
+/// let metrics = tidepool::Metrics::new();
+/// tidepool::handle_connection(stream, &metrics).ok();
+/// ```
+pub fn handle_connection(mut stream: TcpStream, metrics: &Metrics) -> io::Result<ServerControl> {
+    let req = match parse_request(&stream) {
+        Ok(req) => req,
+        Err(_) => {
+            let body_len = write_error_response(&mut stream, "HTTP/1.1 400 BAD REQUEST", "public/400.html", true)?;
+            metrics.record_response("HTTP/1.1 400 BAD REQUEST", body_len);
+            return Ok(ServerControl::Continue);
+        }
+    };
+
+    if req.method == "GET" && req.target == "/stop" {
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")?;
+        metrics.record_response("HTTP/1.1 200 OK", 0);
+        return Ok(ServerControl::Stop);
+    }
+
+    if req.method == "GET" && req.target == "/metrics" {
+        let body = metrics.render();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nContent-Type: text/plain\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(response.as_bytes())?;
+        metrics.record_response("HTTP/1.1 200 OK", body.len());
+        return Ok(ServerControl::Continue);
+    }
+
+    let (status_line, filename) = match req.method.as_str() {
+        "GET" | "HEAD" => resolve_target(&req.target),
+        _ => ("HTTP/1.1 405 METHOD NOT ALLOWED", "public/400.html".to_string()),
+    };
+
+    let send_body = req.method != "HEAD";
+
+    match fs::read(&filename) {
+        Ok(body) => {
+            let content_type = content_type_for(&filename);
+            let mut response = format!(
+                "{status_line}\r\nContent-Length: {}\r\nContent-Type: {content_type}\r\n\r\n",
+                body.len()
+            )
+            .into_bytes();
+
+            if send_body {
+                response.extend_from_slice(&body);
+            }
+
+            stream.write_all(&response)?;
+            metrics.record_response(status_line, if send_body { body.len() } else { 0 });
+        }
+        Err(_) => {
+            // `filename` is whatever `status_line` already resolved to (a real
+            // file, or an error page like `public/400.html` for a `405`), so
+            // falling back through it here keeps the status line honest
+            // instead of silently turning e.g. a missing `public/400.html`
+            // into a `404`.
+            let body_len = write_error_response(&mut stream, status_line, &filename, send_body)?;
+            metrics.record_response(status_line, body_len);
+        }
+    }
+
+    Ok(ServerControl::Continue)
+}
+
+/// A handle to a running [`serve`] accept loop.
+///
+/// Dropping a `Listening` (or calling [`Listening::join`] explicitly) blocks
+/// until the accept loop has stopped and `pool`'s own `Drop` has drained
+/// every already-queued job, so callers get one guard that waits for all of
+/// it instead of having to reason about when the `ThreadPool` value goes out
+/// of scope.
+pub struct Listening {
+    shutdown: Arc<AtomicBool>,
+    accept_thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Listening {
+    /// Requests a clean stop of the accept loop.
+    ///
+    /// Like the `/stop` route, this only takes effect once the loop notices
+    /// it — if it's currently blocked in `incoming()`, one more connection
+    /// may be needed to unblock it and see the request.
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+    }
+
+    /// Blocks until the accept loop has stopped and the pool has drained.
+    pub fn join(mut self) {
+        self.join_accept_thread();
+    }
+
+    fn join_accept_thread(&mut self) {
+        if let Some(thread) = self.accept_thread.take() {
+            thread.join().expect("accept loop thread panicked");
+        }
+    }
+}
+
+impl Drop for Listening {
+    fn drop(&mut self) {
+        self.join_accept_thread();
+    }
+}
+
+/// Runs the accept loop for `listener` on a dedicated thread, dispatching
+/// each connection to `pool` via `handler`, and returns a [`Listening`]
+/// guard.
+///
+/// This is the same accept-loop / [`ServerControl`] coordination `main`
+/// used to own directly, now behind a first-class handle: [`Listening::shutdown`]
+/// requests a stop programmatically, and a `GET /stop` handled by `handler`
+/// still works the same way, by returning [`ServerControl::Stop`]. If
+/// `handler` returns `Err` (e.g. the client disconnected mid-write), the
+/// error is logged and the connection is treated as [`ServerControl::Continue`]
+/// rather than taking down the worker. The loop also calls
+/// [`ThreadPool::reap_and_respawn`] once per iteration, which only does
+/// anything for a `pool` built with [`ThreadPool::new_supervised`].
 ///
 /// # Panics
 ///
-/// This function panics if:
-/// - It fails to read the request line from the stream
-/// - It fails to read the requested HTML file from disk
-/// - It fails to write the response to the stream
+/// The spawned accept-loop thread panics if accepting a connection fails,
+/// which [`Listening::join`] (or the `Drop` impl) will propagate as a panic
+/// when it joins that thread.
 ///
 /// # Examples
 ///
 /// ```no_run
-/// use std::net::TcpStream;
-/// tidepool::handle_connection(stream);
+/// use riotpool::ThreadPool;
+///
+/// let listener = tidepool::bind_with_retry(
+///     std::time::Duration::from_secs(5),
+///     std::net::SocketAddrV4::new(std::net::Ipv4Addr::LOCALHOST, 7878),
+/// )
+/// .unwrap();
+/// let pool = ThreadPool::new(8);
+/// let metrics = tidepool::Metrics::new();
+///
+/// let listening = tidepool::serve(listener, pool, move |stream| {
+///     tidepool::handle_connection(stream, &metrics)
+/// });
+/// listening.join();
 /// ```
-pub fn handle_connection(mut stream: TcpStream) {
-    let request_line = BufReader::new(&stream)
-        .lines()
-        .next()
-        .expect("failed to get the next item")
-        .expect("failed to read from stream");
-
-    let full_path;
-    let (status_line, filename) = if request_line.starts_with("GET ") && request_line.ends_with(" HTTP/1.1") {
-        let path = request_line[4..request_line.len() - 9].trim(); // extract /path
-
-        if path == "/" || path.is_empty() {
-            ("HTTP/1.1 200 OK", "public/index.html")
-        } else if path == "/sleep" {
-            thread::sleep(Duration::from_secs(5));
-            ("HTTP/1.1 200 OK", "public/index.html")
-        } else {
-            // Serve any other file from the "public" directory
-            let sanitized_path = if path.starts_with('/') { &path[1..] } else { path };
+pub fn serve(
+    listener: TcpListener,
+    mut pool: ThreadPool,
+    handler: impl Fn(TcpStream) -> io::Result<ServerControl> + Send + Sync + 'static,
+) -> Listening {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let shutdown_requested = Arc::clone(&shutdown);
 
-            // Basic security: prevent directory traversal
-            if sanitized_path.contains("..") || sanitized_path.contains('\\') {
-                ("HTTP/1.1 400 BAD REQUEST", "public/400.html")
-            } else {
-                 full_path = format!("public/{}", sanitized_path);
+    let accept_thread = thread::spawn(move || {
+        let handler = Arc::new(handler);
+        let (control_tx, control_rx) = mpsc::channel::<ServerControl>();
+        let mut incoming = listener.incoming();
 
-                // If file exists → serve it, else 404
-                if Path::new(&full_path).exists() {
-                    ("HTTP/1.1 200 OK", full_path.as_str())
-                } else {
-                    ("HTTP/1.1 404 NOT FOUND", "public/404.html")
-                }
+        loop {
+            // No-op unless `pool` is supervised; keeps it at full strength
+            // between accepts if a worker retired itself after repeated panics.
+            pool.reap_and_respawn();
+
+            // Checked before the next (blocking) accept; see `Listening::shutdown`
+            // for why a final connection may still be needed to notice this.
+            if shutdown_requested.load(Ordering::Relaxed)
+                || control_rx
+                    .try_iter()
+                    .any(|control| matches!(control, ServerControl::Stop))
+            {
+                println!("stop requested, shutting down");
+                break;
             }
+
+            let Some(stream) = incoming.next() else {
+                break;
+            };
+            let stream = stream.expect("failed to read the stream");
+            println!("incoming request");
+
+            let handler = Arc::clone(&handler);
+            let control_tx = control_tx.clone();
+            pool.execute(move || {
+                let control = handler(stream).unwrap_or_else(|e| {
+                    eprintln!("connection error (client likely gone): {e}");
+                    ServerControl::Continue
+                });
+                // `control_rx` outlives every worker, so this can't fail.
+                control_tx
+                    .send(control)
+                    .expect("failed to report server control signal");
+            });
         }
-    } else {
-        ("HTTP/1.1 400 BAD REQUEST", "public/400.html")
-    };
 
-    // Rest remains the same...
-    let body = fs::read_to_string(filename).expect("failed to read the file");
-    let body_length = body.len();
-    let response = format!(
-        "{status_line}\r\nContent-Length: {body_length}\r\n\r\n{body}"
-    );
+        // Dropping `pool` here joins every worker, letting in-flight
+        // requests (including the one that asked to stop) finish first.
+        drop(pool);
+        println!("Shutting down.");
+    });
 
-    stream
-        .write_all(response.as_bytes())
-        .expect("failed to write to stream");
+    Listening {
+        shutdown,
+        accept_thread: Some(accept_thread),
+    }
 }
 
 /// Attempts to bind a `TcpListener` to the given address, retrying every 300ms
@@ -148,4 +533,115 @@ pub fn initializing(port: u16, number_of_threads: usize) -> (SocketAddrV4, Durat
     );
 
     (local_host, timeout, number_of_threads)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    /// Connects a loopback client/server pair and writes `request` on the
+    /// client side, returning the server-side `TcpStream` for `parse_request`
+    /// to read from — there's no stream-mocking abstraction in this crate, so
+    /// a real socket is the simplest way to exercise it.
+    fn request_stream(request: &str) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+
+        let (server, _) = listener.accept().unwrap();
+        server
+    }
+
+    #[test]
+    fn parse_request_reads_method_target_version_and_headers() {
+        let stream = request_stream("GET /index.html HTTP/1.1\r\nHost: localhost\r\nAccept: */*\r\n\r\n");
+
+        let req = parse_request(&stream).unwrap();
+
+        assert_eq!(req.method, "GET");
+        assert_eq!(req.target, "/index.html");
+        assert_eq!(req.version, "HTTP/1.1");
+        assert_eq!(
+            req.headers,
+            vec![
+                ("Host".to_string(), "localhost".to_string()),
+                ("Accept".to_string(), "*/*".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_request_tolerates_headers_without_a_space_after_the_colon() {
+        let stream = request_stream("GET / HTTP/1.1\r\nHost:localhost\r\n\r\n");
+
+        let req = parse_request(&stream).unwrap();
+
+        assert_eq!(req.headers, vec![("Host".to_string(), "localhost".to_string())]);
+    }
+
+    #[test]
+    fn parse_request_rejects_a_header_with_no_colon() {
+        let stream = request_stream("GET / HTTP/1.1\r\nnot-a-header\r\n\r\n");
+
+        let err = match parse_request(&stream) {
+            Err(err) => err,
+            Ok(_) => panic!("expected a malformed header line to be rejected"),
+        };
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn resolve_target_serves_index_for_root() {
+        let (status_line, filename) = resolve_target("/");
+
+        assert_eq!(status_line, "HTTP/1.1 200 OK");
+        assert_eq!(filename, "public/index.html");
+    }
+
+    #[test]
+    fn resolve_target_rejects_path_traversal() {
+        let (status_line, filename) = resolve_target("/../secrets.txt");
+
+        assert_eq!(status_line, "HTTP/1.1 400 BAD REQUEST");
+        assert_eq!(filename, "public/400.html");
+    }
+
+    #[test]
+    fn metrics_bucket_by_status_class_and_sum_bytes() {
+        let metrics = Metrics::new();
+
+        metrics.record_response("HTTP/1.1 200 OK", 10);
+        metrics.record_response("HTTP/1.1 404 NOT FOUND", 20);
+        metrics.record_response("HTTP/1.1 500 INTERNAL SERVER ERROR", 30);
+
+        let rendered = metrics.render();
+
+        assert!(rendered.contains("total_requests 3"));
+        assert!(rendered.contains("2xx 1"));
+        assert!(rendered.contains("4xx 1"));
+        assert!(rendered.contains("5xx 1"));
+        assert!(rendered.contains("bytes_served 60"));
+    }
+
+    #[test]
+    fn listening_join_returns_once_shutdown_is_noticed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let pool = ThreadPool::new(1);
+
+        let listening = serve(listener, pool, |_stream| Ok(ServerControl::Continue));
+        listening.shutdown();
+
+        // The accept loop only checks `shutdown` between accepts: if it's
+        // already blocked in `incoming()`, it needs one more connection to
+        // notice. If it saw the flag before accepting, the listener may
+        // already be closed, so this connect is allowed to fail.
+        let _ = TcpStream::connect(addr);
+
+        listening.join();
+    }
 }
\ No newline at end of file
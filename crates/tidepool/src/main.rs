@@ -8,6 +8,8 @@
 //! - 404 handling for unknown paths
 //! - Graceful binding retry on startup
 //! - Thread pool powered by the `riotpool` crate
+//! - `GET /stop` shuts the server down gracefully
+//! - `GET /metrics` reports request counters
 //!
 //! Example usage:
 //! ```bash
@@ -16,7 +18,7 @@
 //! Then visit `http://127.0.0.1:7878/` in your browser.
 
 use riotpool::ThreadPool;
-use tidepool::{bind_with_retry, handle_connection, initializing};
+use tidepool::{Metrics, bind_with_retry, handle_connection, initializing, serve};
 
 fn main() {
     // Initialize configuration: ip localhost(127.0.0.1), port 7878, 8 worker threads
@@ -29,14 +31,14 @@ fn main() {
     // Create a thread pool to handle incoming connections concurrently
     let pool = ThreadPool::new(number_of_threads);
 
-    // Accept incoming connections and dispatch them to the pool
-    for stream in listener.incoming() {
-        let stream = stream.expect("failed to read the stream");
-        println!("incoming request");
+    // Shared across every job so `GET /metrics` reports totals for the
+    // whole server, not just one worker.
+    let metrics = Metrics::new();
 
-        pool.execute(|| handle_connection(stream));
-    }
-
-    // This line is reached only if the listener stops (e.g., on error)
-    println!("Shutting down.");
-}
\ No newline at end of file
+    // `serve` owns the accept loop from here; joining the returned guard
+    // blocks until the loop stops (e.g. via `GET /stop`) and the pool drains.
+    let listening = serve(listener, pool, move |stream| {
+        handle_connection(stream, &metrics)
+    });
+    listening.join();
+}